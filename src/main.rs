@@ -31,12 +31,29 @@
 #![allow(clippy::cast_possible_truncation)]
 #![allow(clippy::cast_sign_loss)]
 
-use std::{fs::File, ptr::null_mut, sync::Arc};
+use std::{
+    cell::RefCell,
+    fs::File,
+    io::Cursor,
+    num::{NonZeroU32, NonZeroU8},
+    path::{Path, PathBuf},
+    ptr::null_mut,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use nwd::NwgUi;
 use nwg::{CheckBoxState, NativeUi};
 use rustysynth::{MidiFile, MidiFileSequencer, SoundFont, Synthesizer, SynthesizerSettings};
-use winapi::um::winuser::{MessageBoxW, MB_OK};
+use winapi::um::{
+    processthreadsapi::{GetCurrentThread, SetThreadPriority},
+    winbase::THREAD_PRIORITY_HIGHEST,
+    winuser::{MessageBoxW, MB_OK},
+};
 
 const fn checkbox_state_as_bool(state: CheckBoxState) -> bool {
     match state {
@@ -45,6 +62,512 @@ const fn checkbox_state_as_bool(state: CheckBoxState) -> bool {
     }
 }
 
+const OUTPUT_FORMAT_LABELS: [&str; 5] = [
+    "32-bit Float",
+    "16-bit PCM",
+    "24-bit PCM",
+    "FLAC",
+    "Ogg Vorbis",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Float32,
+    Pcm16,
+    Pcm24,
+    Flac,
+    Vorbis,
+}
+
+impl OutputFormat {
+    fn from_label(label: &str) -> Self {
+        match label {
+            "16-bit PCM" => Self::Pcm16,
+            "24-bit PCM" => Self::Pcm24,
+            "FLAC" => Self::Flac,
+            "Ogg Vorbis" => Self::Vorbis,
+            _ => Self::Float32,
+        }
+    }
+
+    // The filename extension this format is saved under. `Pcm16`/`Pcm24`/`Float32` all share
+    // `.wav`, since the bit depth isn't recoverable from the extension alone.
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Float32 | Self::Pcm16 | Self::Pcm24 => "wav",
+            Self::Flac => "flac",
+            Self::Vorbis => "ogg",
+        }
+    }
+
+    // Whether `path`'s extension already matches this format's family (e.g. any `.wav` variant
+    // matches any of `Float32`/`Pcm16`/`Pcm24`), so the UI can leave the bit-depth choice alone
+    // instead of clobbering it every time the path is merely re-saved under the same extension.
+    fn matches_extension(self, path: &str) -> bool {
+        Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(self.extension()))
+    }
+}
+
+// MT-32 program number -> closest General MIDI program. Identity by default, since the
+// first 96 MT-32 patches roughly track GM's instrument families in the same order; the
+// last 32 (MT-32 "Sound Effects" groups) have no GM counterpart in that order, so they're
+// remapped onto GM's own sound-effect programs (120-127), cycling through those eight.
+const MT32_TO_GM_PROGRAM: [u8; 128] = {
+    let mut table = [0_u8; 128];
+    let mut i = 0;
+    while i < 96 {
+        table[i] = i as u8;
+        i += 1;
+    }
+    while i < 128 {
+        table[i] = (120 + (i - 96) % 8) as u8;
+        i += 1;
+    }
+    table
+};
+
+// MT-32 rhythm key -> General MIDI drum map note, for the MT-32's dedicated percussion
+// channel (channel 10). Keys outside this range are passed through unchanged.
+const MT32_DRUM_BASE: u8 = 24;
+const MT32_TO_GM_DRUM: [u8; 64] = [
+    36, 36, 37, 38, 40, 37, 38, 47, 43, 43, 39, 35, 36, 36, 42, 42, // 24-39
+    44, 46, 49, 51, 51, 49, 57, 55, 52, 53, 41, 45, 48, 47, 50, 41, // 40-55
+    45, 48, 50, 39, 54, 56, 58, 42, 46, 72, 73, 74, 75, 76, 77, 78, // 56-71
+    79, 80, 81, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, // 72-87
+];
+
+fn mt32_key_to_gm_drum(key: u8) -> u8 {
+    key.checked_sub(MT32_DRUM_BASE)
+        .and_then(|offset| MT32_TO_GM_DRUM.get(offset as usize))
+        .copied()
+        .unwrap_or(key)
+}
+
+fn read_midi_varint_len(data: &[u8]) -> usize {
+    let mut count = 0;
+    for &byte in data {
+        count += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    count
+}
+
+fn parse_midi_varint(data: &[u8]) -> usize {
+    let mut value: usize = 0;
+    for &byte in data {
+        value = (value << 7) | usize::from(byte & 0x7F);
+    }
+    value
+}
+
+// Walks the channel-voice events (note on/off, CC, program change, ...) of a single MTrk
+// chunk, handling running status, and hands each one's status byte and data bytes to
+// `on_event` so callers can rewrite them in place. Meta and sysex events are skipped over
+// untouched.
+fn walk_midi_channel_events<F: FnMut(u8, &mut [u8])>(data: &mut [u8], mut on_event: F) {
+    let mut pos = 0;
+    let mut running_status = 0_u8;
+
+    while pos < data.len() {
+        // delta-time
+        pos += read_midi_varint_len(&data[pos..]);
+        if pos >= data.len() {
+            break;
+        }
+
+        if data[pos] & 0x80 != 0 {
+            running_status = data[pos];
+            pos += 1;
+        }
+
+        match running_status {
+            0xFF => {
+                // meta event: type byte, then a length-prefixed payload
+                pos += 1;
+                if pos >= data.len() {
+                    break;
+                }
+                let len_bytes = read_midi_varint_len(&data[pos..]);
+                let payload_len = parse_midi_varint(&data[pos..pos + len_bytes]);
+                pos += len_bytes + payload_len;
+            }
+            0xF0 | 0xF7 => {
+                let len_bytes = read_midi_varint_len(&data[pos..]);
+                let payload_len = parse_midi_varint(&data[pos..pos + len_bytes]);
+                pos += len_bytes + payload_len;
+            }
+            status if status & 0xF0 == 0xC0 || status & 0xF0 == 0xD0 => {
+                if pos < data.len() {
+                    on_event(status, &mut data[pos..pos + 1]);
+                }
+                pos += 1;
+            }
+            status if status >= 0x80 => {
+                if pos + 1 < data.len() {
+                    on_event(status, &mut data[pos..pos + 2]);
+                }
+                pos += 2;
+            }
+            _ => break,
+        }
+    }
+}
+
+// Walks the MTrk chunks of a standard MIDI file, handing each one's raw bytes to `on_track`.
+fn walk_midi_file_tracks<F: FnMut(&mut [u8])>(bytes: &mut [u8], mut on_track: F) {
+    if bytes.len() < 14 || &bytes[0..4] != b"MThd" {
+        return;
+    }
+
+    let header_len = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    let mut pos = 8 + header_len;
+
+    while pos + 8 <= bytes.len() {
+        let chunk_id = [bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]];
+        let chunk_len = u32::from_be_bytes([
+            bytes[pos + 4],
+            bytes[pos + 5],
+            bytes[pos + 6],
+            bytes[pos + 7],
+        ]) as usize;
+        let data_start = pos + 8;
+        let data_end = (data_start + chunk_len).min(bytes.len());
+
+        if &chunk_id == b"MTrk" {
+            on_track(&mut bytes[data_start..data_end]);
+        }
+
+        pos = data_end;
+    }
+}
+
+fn remap_mt32_to_gm(bytes: &mut [u8]) {
+    walk_midi_file_tracks(bytes, |track| {
+        walk_midi_channel_events(track, |status, data| match status & 0xF0 {
+            0xC0 => data[0] = MT32_TO_GM_PROGRAM[(data[0] & 0x7F) as usize],
+            0x80 | 0x90 if status & 0x0F == 9 => data[0] = mt32_key_to_gm_drum(data[0]),
+            _ => {}
+        });
+    });
+}
+
+#[derive(Clone, Copy)]
+struct MixerChannel {
+    mute: bool,
+    solo: bool,
+    gain: f32,
+}
+
+fn apply_mixer_to_midi(bytes: &mut [u8], channels: &[MixerChannel]) {
+    let solo_active = channels.iter().any(|channel| channel.solo);
+
+    walk_midi_file_tracks(bytes, |track| {
+        walk_midi_channel_events(track, |status, data| {
+            if status & 0xF0 != 0x90 || data[1] == 0 {
+                return;
+            }
+
+            let Some(channel) = channels.get(usize::from(status & 0x0F)) else {
+                return;
+            };
+
+            if channel.mute || (solo_active && !channel.solo) {
+                data[1] = 0;
+            } else {
+                data[1] = (f32::from(data[1]) * channel.gain).clamp(0.0, 127.0) as u8;
+            }
+        });
+    });
+}
+
+struct MixerPanel {
+    window: nwg::Window,
+    // only held so the channel labels aren't destroyed when they'd otherwise be dropped
+    labels: Vec<nwg::Label>,
+    mute_boxes: Vec<nwg::CheckBox>,
+    solo_boxes: Vec<nwg::CheckBox>,
+    gain_bars: Vec<nwg::TrackBar>,
+}
+
+fn open_mixer_panel(mixer: &Arc<Mutex<Vec<MixerChannel>>>) -> Rc<MixerPanel> {
+    let mut window = Default::default();
+    nwg::Window::builder()
+        .size((280, 420))
+        .title("Mixer")
+        .flags(nwg::WindowFlags::WINDOW | nwg::WindowFlags::VISIBLE)
+        .build(&mut window)
+        .expect("building mixer window failed");
+
+    let mut labels = Vec::with_capacity(16);
+    let mut mute_boxes = Vec::with_capacity(16);
+    let mut solo_boxes = Vec::with_capacity(16);
+    let mut gain_bars = Vec::with_capacity(16);
+
+    for channel in 0_i32..16 {
+        let y = 4 + channel * 24;
+
+        let mut label = Default::default();
+        nwg::Label::builder()
+            .text(&format!("Ch {}", channel + 1))
+            .parent(&window)
+            .position((4, y))
+            .size((40, 20))
+            .build(&mut label)
+            .expect("building mixer channel label failed");
+        // the label isn't read back, but dropping it would destroy it, so it's stored too
+        labels.push(label);
+
+        let mut mute = Default::default();
+        nwg::CheckBox::builder()
+            .text("Mute")
+            .parent(&window)
+            .position((48, y))
+            .size((50, 20))
+            .build(&mut mute)
+            .expect("building mute checkbox failed");
+        mute_boxes.push(mute);
+
+        let mut solo = Default::default();
+        nwg::CheckBox::builder()
+            .text("Solo")
+            .parent(&window)
+            .position((102, y))
+            .size((50, 20))
+            .build(&mut solo)
+            .expect("building solo checkbox failed");
+        solo_boxes.push(solo);
+
+        let mut gain = Default::default();
+        nwg::TrackBar::builder()
+            .range(Some(0..150))
+            .pos(Some(100))
+            .parent(&window)
+            .position((156, y))
+            .size((110, 20))
+            .build(&mut gain)
+            .expect("building gain trackbar failed");
+        gain_bars.push(gain);
+    }
+
+    let panel = Rc::new(MixerPanel {
+        window,
+        labels,
+        mute_boxes,
+        solo_boxes,
+        gain_bars,
+    });
+
+    // The main window gets `close` via `#[nwg_events(OnWindowClose: ...)]`, but the mixer
+    // panel is built by hand, so its close handler has to be bound the same way as the
+    // checkbox/trackbar handlers below. Hide instead of letting the HWND be destroyed, or
+    // `FireSynth::mixer_panel` would keep pointing at a dead window after the first close.
+    let window_handle = panel.window.handle;
+    let bound_panel = Rc::clone(&panel);
+    nwg::bind_event_handler(
+        &window_handle,
+        &window_handle,
+        move |event, data, event_handle| {
+            if event == nwg::Event::OnWindowClose && event_handle == window_handle {
+                if let nwg::EventData::OnWindowClose(close_data) = data {
+                    close_data.close(false);
+                }
+                bound_panel.window.set_visible(false);
+            }
+        },
+    );
+
+    for channel in 0..16_usize {
+        let mixer = Arc::clone(mixer);
+        let bound_panel = Rc::clone(&panel);
+        let handle = panel.mute_boxes[channel].handle;
+        nwg::bind_event_handler(&handle, &panel.window.handle, move |event, _data, event_handle| {
+            if event == nwg::Event::OnButtonClick && event_handle == handle {
+                let checked = checkbox_state_as_bool(bound_panel.mute_boxes[channel].check_state());
+                mixer.lock().expect("locking mixer failed")[channel].mute = checked;
+            }
+        });
+
+        let mixer = Arc::clone(mixer);
+        let bound_panel = Rc::clone(&panel);
+        let handle = panel.solo_boxes[channel].handle;
+        nwg::bind_event_handler(&handle, &panel.window.handle, move |event, _data, event_handle| {
+            if event == nwg::Event::OnButtonClick && event_handle == handle {
+                let checked = checkbox_state_as_bool(bound_panel.solo_boxes[channel].check_state());
+                mixer.lock().expect("locking mixer failed")[channel].solo = checked;
+            }
+        });
+
+        let mixer = Arc::clone(mixer);
+        let bound_panel = Rc::clone(&panel);
+        let handle = panel.gain_bars[channel].handle;
+        nwg::bind_event_handler(&handle, &panel.window.handle, move |event, _data, event_handle| {
+            if event == nwg::Event::OnHorizontalScroll && event_handle == handle {
+                let pos = bound_panel.gain_bars[channel].pos();
+                mixer.lock().expect("locking mixer failed")[channel].gain = pos as f32 / 100.0;
+            }
+        });
+    }
+
+    panel
+}
+
+fn scale_to_pcm(sample: f32, bits: u32) -> i32 {
+    let full_scale = f64::from((1_i32 << (bits - 1)) - 1);
+    (f64::from(sample) * full_scale).clamp(-full_scale - 1.0, full_scale) as i32
+}
+
+trait ChunkWriter {
+    fn write_chunk(&mut self, left: &[f32], right: &[f32]);
+    fn finish(self: Box<Self>);
+}
+
+struct WavFloatWriter(hound::WavWriter<std::io::BufWriter<File>>);
+
+impl ChunkWriter for WavFloatWriter {
+    fn write_chunk(&mut self, left: &[f32], right: &[f32]) {
+        for (l, r) in left.iter().zip(right.iter()) {
+            self.0
+                .write_sample(*l)
+                .expect("writing wav left channel failed");
+            self.0
+                .write_sample(*r)
+                .expect("writing wav right channel failed");
+        }
+    }
+
+    fn finish(self: Box<Self>) {
+        self.0.finalize().expect("finalizing WAV writer failed");
+    }
+}
+
+struct WavPcmWriter {
+    writer: hound::WavWriter<std::io::BufWriter<File>>,
+    bits: u32,
+}
+
+impl ChunkWriter for WavPcmWriter {
+    fn write_chunk(&mut self, left: &[f32], right: &[f32]) {
+        for (l, r) in left.iter().zip(right.iter()) {
+            self.writer
+                .write_sample(scale_to_pcm(*l, self.bits))
+                .expect("writing wav left channel failed");
+            self.writer
+                .write_sample(scale_to_pcm(*r, self.bits))
+                .expect("writing wav right channel failed");
+        }
+    }
+
+    fn finish(self: Box<Self>) {
+        self.writer.finalize().expect("finalizing WAV writer failed");
+    }
+}
+
+struct FlacWriter(flac_bound::FlacEncoder<'static>);
+
+impl ChunkWriter for FlacWriter {
+    fn write_chunk(&mut self, left: &[f32], right: &[f32]) {
+        let mut interleaved: Vec<i32> = Vec::with_capacity(left.len() * 2);
+        for (l, r) in left.iter().zip(right.iter()) {
+            interleaved.push(scale_to_pcm(*l, 16));
+            interleaved.push(scale_to_pcm(*r, 16));
+        }
+
+        self.0
+            .process_interleaved(
+                &interleaved,
+                left.len()
+                    .try_into()
+                    .expect("converting sample count into u32 failed"),
+            )
+            .expect("encoding FLAC failed");
+    }
+
+    fn finish(self: Box<Self>) {
+        self.0.finish().expect("finishing FLAC encoder failed");
+    }
+}
+
+struct VorbisWriter(vorbis_rs::VorbisEncoder<File>);
+
+impl ChunkWriter for VorbisWriter {
+    fn write_chunk(&mut self, left: &[f32], right: &[f32]) {
+        self.0
+            .encode_audio_block(&[left, right])
+            .expect("encoding Ogg Vorbis failed");
+    }
+
+    fn finish(self: Box<Self>) {
+        self.0.finish().expect("finishing Vorbis encoder failed");
+    }
+}
+
+fn make_writer(format: OutputFormat, path: &str, sample_rate: i32) -> Box<dyn ChunkWriter> {
+    let sample_rate_u32: u32 = sample_rate
+        .try_into()
+        .expect("converting sample_rate into u32 failed");
+
+    match format {
+        OutputFormat::Float32 => {
+            let spec = hound::WavSpec {
+                channels: 2,
+                sample_rate: sample_rate_u32,
+                bits_per_sample: 32,
+                sample_format: hound::SampleFormat::Float,
+            };
+            Box::new(WavFloatWriter(
+                hound::WavWriter::create(path, spec).expect("creating WAV writer failed"),
+            ))
+        }
+        OutputFormat::Pcm16 | OutputFormat::Pcm24 => {
+            let bits = if format == OutputFormat::Pcm16 { 16 } else { 24 };
+            let spec = hound::WavSpec {
+                channels: 2,
+                sample_rate: sample_rate_u32,
+                bits_per_sample: bits as u16,
+                sample_format: hound::SampleFormat::Int,
+            };
+            Box::new(WavPcmWriter {
+                writer: hound::WavWriter::create(path, spec).expect("creating WAV writer failed"),
+                bits,
+            })
+        }
+        OutputFormat::Flac => {
+            let encoder = flac_bound::FlacEncoder::new()
+                .expect("creating FLAC encoder failed")
+                .channels(2)
+                .bits_per_sample(16)
+                .sample_rate(sample_rate_u32)
+                .init_file(path)
+                .expect("initializing FLAC encoder failed");
+            Box::new(FlacWriter(encoder))
+        }
+        OutputFormat::Vorbis => {
+            let file = File::create(path).expect("creating Ogg Vorbis file failed");
+            let encoder = vorbis_rs::VorbisEncoderBuilder::new(
+                NonZeroU32::new(sample_rate_u32).expect("sample rate must be nonzero"),
+                NonZeroU8::new(2).expect("constructing channel count failed"),
+                file,
+            )
+            .expect("creating Vorbis encoder failed")
+            .build()
+            .expect("building Vorbis encoder failed");
+            Box::new(VorbisWriter(encoder))
+        }
+    }
+}
+
+#[derive(Clone)]
+struct RenderState {
+    cancel: Arc<AtomicBool>,
+    progress: Arc<AtomicUsize>,
+    total_frames: usize,
+}
+
 #[derive(Default, NwgUi)]
 pub struct FireSynth {
     #[nwg_control(size: (300, 300), title: "FireSynth", flags: "WINDOW|VISIBLE|RESIZABLE")]
@@ -74,6 +597,43 @@ pub struct FireSynth {
     #[nwg_events(OnButtonClick: [FireSynth::render])]
     render_button: nwg::Button,
 
+    #[nwg_control(text: "Play")]
+    #[nwg_layout_item(layout: grid, col: 0, row: 8, row_span: 2)]
+    #[nwg_events(OnButtonClick: [FireSynth::play])]
+    play_button: nwg::Button,
+
+    #[nwg_control(text: "Stop")]
+    #[nwg_layout_item(layout: grid, col: 1, row: 8, row_span: 2)]
+    #[nwg_events(OnButtonClick: [FireSynth::stop])]
+    stop_button: nwg::Button,
+
+    #[nwg_control(text: "MIDI Input")]
+    #[nwg_layout_item(layout: grid, col: 0, row: 10, col_span: 2)]
+    input_label: nwg::Label,
+
+    #[nwg_control]
+    #[nwg_layout_item(layout: grid, col: 0, row: 11, col_span: 2)]
+    #[nwg_events(OnComboBoxSelection: [FireSynth::input_select])]
+    input_combo: nwg::ComboBox<String>,
+
+    #[nwg_control(range: 0..100)]
+    #[nwg_layout_item(layout: grid, col: 0, row: 12, col_span: 4)]
+    progress_bar: nwg::ProgressBar,
+
+    #[nwg_control(text: "Cancel")]
+    #[nwg_layout_item(layout: grid, col: 0, row: 13, col_span: 2)]
+    #[nwg_events(OnButtonClick: [FireSynth::cancel_render])]
+    cancel_button: nwg::Button,
+
+    #[nwg_control(text: "Mixer")]
+    #[nwg_layout_item(layout: grid, col: 2, row: 13, col_span: 2)]
+    #[nwg_events(OnButtonClick: [FireSynth::open_mixer])]
+    mixer_button: nwg::Button,
+
+    #[nwg_control]
+    #[nwg_events(OnNotice: [FireSynth::render_progress])]
+    progress_notice: nwg::Notice,
+
     #[nwg_control(text: "MIDI Path")]
     #[nwg_layout_item(layout: grid, col: 2, row: 0, col_span: 2)]
     midi_label: nwg::Label,
@@ -110,18 +670,67 @@ pub struct FireSynth {
     #[nwg_layout_item(layout: grid, col: 2, row: 8, col_span: 2)]
     reverb: nwg::CheckBox,
 
+    #[nwg_control(text: "MT-32 -> GM")]
+    #[nwg_layout_item(layout: grid, col: 2, row: 11, col_span: 2)]
+    mt32_to_gm: nwg::CheckBox,
+
+    #[nwg_control(text: "Output Format")]
+    #[nwg_layout_item(layout: grid, col: 2, row: 9, col_span: 2)]
+    format_label: nwg::Label,
+
+    #[nwg_control]
+    #[nwg_layout_item(layout: grid, col: 2, row: 10, col_span: 2)]
+    #[nwg_events(OnComboBoxSelection: [FireSynth::format_changed])]
+    format_combo: nwg::ComboBox<String>,
+
     #[nwg_resource(title: "Open MIDI", action: nwg::FileDialogAction::Open, filters: "MIDI(*.mid;*.midi)")]
     midi_dialog: nwg::FileDialog,
 
     #[nwg_resource(title: "Open SoundFont", action: nwg::FileDialogAction::Open, filters: "SoundFont(*.sf;*.sf2;*.sf3)")]
     sf_dialog: nwg::FileDialog,
 
-    #[nwg_resource(title: "Save File", action: nwg::FileDialogAction::Save, filters: "WAV(*.wav)")]
+    #[nwg_resource(title: "Save File", action: nwg::FileDialogAction::Save, filters: "WAV(*.wav)|FLAC(*.flac)|Ogg Vorbis(*.ogg)")]
     save_file_dialog: nwg::FileDialog,
+
+    preview_stream: RefCell<Option<cpal::Stream>>,
+    midi_connection: RefCell<Option<midir::MidiInputConnection<()>>>,
+    render_state: RefCell<Option<RenderState>>,
+    mixer: Arc<Mutex<Vec<MixerChannel>>>,
+    mixer_panel: RefCell<Option<Rc<MixerPanel>>>,
 }
 
 impl FireSynth {
-    fn window_init(_: &Self) {
+    fn window_init(&self) {
+        let midi_in =
+            midir::MidiInput::new("FireSynth port enumeration").expect("creating MIDI input failed");
+        let port_names: Vec<String> = midi_in
+            .ports()
+            .iter()
+            .map(|port| {
+                midi_in
+                    .port_name(port)
+                    .expect("getting MIDI port name failed")
+            })
+            .collect();
+        self.input_combo.set_collection(port_names);
+
+        self.format_combo.set_collection(
+            OUTPUT_FORMAT_LABELS
+                .iter()
+                .map(|label| (*label).to_owned())
+                .collect(),
+        );
+        self.format_combo.set_selection(Some(0));
+
+        *self.mixer.lock().expect("locking mixer failed") = vec![
+            MixerChannel {
+                mute: false,
+                solo: false,
+                gain: 1.0,
+            };
+            16
+        ];
+
         std::panic::set_hook(Box::new(|info| {
             let backtrace = std::backtrace::Backtrace::force_capture();
 
@@ -167,24 +776,207 @@ impl FireSynth {
         if self.save_file_dialog.run(Some(&self.window)) {
             self.output_path.set_text("");
             if let Ok(dir) = self.save_file_dialog.get_selected_item() {
-                self.output_path
-                    .set_text(&dir.into_string().expect("turning dir into string failed"));
+                let path = dir.into_string().expect("turning dir into string failed");
+                self.output_path.set_text(&path);
+                self.sync_format_combo_from_path(&path);
             }
         }
     }
 
+    // Keeps "Output Format" in agreement with the extension the user picked in the save
+    // dialog: `.wav` keeps whichever WAV format is already selected, `.flac`/`.ogg` select
+    // their own format, and any other extension (or none) selects "32-bit Float" while the
+    // path keeps the extension it was given.
+    fn sync_format_combo_from_path(&self, path: &str) {
+        let current =
+            OutputFormat::from_label(&self.format_combo.selection_string().unwrap_or_default());
+        if current.matches_extension(path) {
+            return;
+        }
+
+        let label = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("flac") => "FLAC",
+            Some(ext) if ext.eq_ignore_ascii_case("ogg") => "Ogg Vorbis",
+            _ => "32-bit Float",
+        };
+        if let Some(index) = OUTPUT_FORMAT_LABELS.iter().position(|l| *l == label) {
+            self.format_combo.set_selection(Some(index));
+        }
+    }
+
+    // Keeps the output path's extension in agreement with "Output Format" when the user changes
+    // the dropdown directly, for the same reason.
+    fn format_changed(&self) {
+        let format =
+            OutputFormat::from_label(&self.format_combo.selection_string().unwrap_or_default());
+        let path = self.output_path.text();
+        if path.is_empty() || format.matches_extension(&path) {
+            return;
+        }
+
+        let mut new_path = PathBuf::from(&path);
+        new_path.set_extension(format.extension());
+        self.output_path
+            .set_text(&new_path.to_string_lossy().into_owned());
+    }
+
     fn render(&self) {
+        if self.render_state.borrow().is_some() {
+            return;
+        }
+
         let sample_rate: i32 = self
             .sample_rate
             .text()
             .parse()
             .expect("parsing sample rate into i32 failed");
 
+        let mut midi_bytes = std::fs::read(self.midi_path.text()).expect("reading MIDI failed");
+        if checkbox_state_as_bool(self.mt32_to_gm.check_state()) {
+            remap_mt32_to_gm(&mut midi_bytes);
+        }
+        apply_mixer_to_midi(
+            &mut midi_bytes,
+            &self.mixer.lock().expect("locking mixer failed"),
+        );
+        let midi_file =
+            Arc::new(MidiFile::new(&mut Cursor::new(midi_bytes)).expect("creating MIDI failed"));
+        let total_frames = (f64::from(sample_rate) * midi_file.get_length()) as usize;
+
+        let sf_path = self.sf_path.text();
+        let output_path = self.output_path.text();
+        let reverb = checkbox_state_as_bool(self.reverb.check_state());
+        let format =
+            OutputFormat::from_label(&self.format_combo.selection_string().unwrap_or_default());
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let progress = Arc::new(AtomicUsize::new(0));
+
+        *self.render_state.borrow_mut() = Some(RenderState {
+            cancel: Arc::clone(&cancel),
+            progress: Arc::clone(&progress),
+            total_frames,
+        });
+
+        self.render_button.set_enabled(false);
+        self.progress_bar.set_pos(0);
+
+        let sender = self.progress_notice.sender();
+
+        std::thread::spawn(move || {
+            // safety: raises this worker's scheduling priority so rendering keeps up while the UI thread stays responsive
+            unsafe {
+                SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_HIGHEST as i32);
+            }
+
+            let mut sf = File::open(sf_path).expect("opening SoundFont failed");
+            let sound_font = SoundFont::new(&mut sf).expect("creating SoundFont failed");
+
+            let mut settings = SynthesizerSettings::new(sample_rate);
+            settings.enable_reverb_and_chorus = reverb;
+            let synthesizer =
+                Synthesizer::new(&sound_font, &settings).expect("creating synthesizer failed");
+            let mut sequencer = MidiFileSequencer::new(synthesizer);
+
+            sequencer.play(&midi_file, false);
+
+            let mut writer = make_writer(format, &output_path, sample_rate);
+
+            const BLOCK_SIZE: usize = 4096;
+            let mut left = vec![0_f32; BLOCK_SIZE];
+            let mut right = vec![0_f32; BLOCK_SIZE];
+            let mut rendered = 0;
+            let mut cancelled = false;
+
+            while rendered < total_frames {
+                if cancel.load(Ordering::Relaxed) {
+                    cancelled = true;
+                    break;
+                }
+
+                let frames = BLOCK_SIZE.min(total_frames - rendered);
+                sequencer.render(&mut left[..frames], &mut right[..frames]);
+                writer.write_chunk(&left[..frames], &right[..frames]);
+
+                rendered += frames;
+                progress.store(rendered, Ordering::Relaxed);
+                sender.notice();
+            }
+
+            if cancelled {
+                drop(writer);
+                let _ = std::fs::remove_file(&output_path);
+            } else {
+                writer.finish();
+            }
+
+            progress.store(total_frames, Ordering::Relaxed);
+            sender.notice();
+        });
+    }
+
+    fn render_progress(&self) {
+        let Some(state) = self.render_state.borrow().clone() else {
+            return;
+        };
+
+        let done = state.progress.load(Ordering::Relaxed);
+        let percent = if state.total_frames == 0 {
+            100
+        } else {
+            ((done * 100) / state.total_frames) as u32
+        };
+        self.progress_bar.set_pos(percent);
+
+        if done >= state.total_frames {
+            self.render_button.set_enabled(true);
+            *self.render_state.borrow_mut() = None;
+
+            if state.cancel.load(Ordering::Relaxed) {
+                nwg::modal_info_message(&self.window, "Cancelled", "Render cancelled");
+            } else {
+                nwg::modal_info_message(&self.window, "Finished", "Done");
+            }
+        }
+    }
+
+    fn cancel_render(&self) {
+        if let Some(state) = self.render_state.borrow().as_ref() {
+            state.cancel.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn play(&self) {
+        self.stop();
+
         let mut sf = File::open(self.sf_path.text()).expect("opening SoundFont failed");
         let sound_font = Arc::new(SoundFont::new(&mut sf).expect("creating SoundFont failed"));
 
-        let mut midi = File::open(self.midi_path.text()).expect("opening MIDI failed");
-        let midi_file = Arc::new(MidiFile::new(&mut midi).expect("creating MIDI failed"));
+        let mut midi_bytes = std::fs::read(self.midi_path.text()).expect("reading MIDI failed");
+        if checkbox_state_as_bool(self.mt32_to_gm.check_state()) {
+            remap_mt32_to_gm(&mut midi_bytes);
+        }
+        // Unlike `input_select`'s callback, which drives the synthesizer one MIDI message at a
+        // time and can re-read `self.mixer` live, `MidiFileSequencer` owns the whole file's
+        // event timeline once handed to `sequencer.play` and mixes every channel down as it
+        // goes, so there's no per-event hook left to intercept here. The mixer is therefore
+        // baked into the file once, at Play time, the same as `render`; toggling mute/solo/gain
+        // mid-playback needs Stop/Play again to take effect.
+        apply_mixer_to_midi(
+            &mut midi_bytes,
+            &self.mixer.lock().expect("locking mixer failed"),
+        );
+        let midi_file =
+            Arc::new(MidiFile::new(&mut Cursor::new(midi_bytes)).expect("creating MIDI failed"));
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .expect("finding default output device failed");
+        let config = device
+            .default_output_config()
+            .expect("getting default output config failed");
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0 as i32;
 
         let mut settings = SynthesizerSettings::new(sample_rate);
         settings.enable_reverb_and_chorus = checkbox_state_as_bool(self.reverb.check_state());
@@ -194,36 +986,213 @@ impl FireSynth {
 
         sequencer.play(&midi_file, false);
 
-        let sample_count = (f64::from(sample_rate) * midi_file.get_length()) as usize;
-        let mut left: Vec<f32> = vec![0_f32; sample_count];
-        let mut right: Vec<f32> = vec![0_f32; sample_count];
+        let sequencer = Arc::new(Mutex::new(sequencer));
+
+        let callback_sequencer = Arc::clone(&sequencer);
+        let mut left: Vec<f32> = vec![0_f32; 1024];
+        let mut right: Vec<f32> = vec![0_f32; 1024];
 
-        sequencer.render(&mut left[..], &mut right[..]);
+        let stream = device
+            .build_output_stream(
+                &config.into(),
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    let frames = data.len() / channels;
+                    left.resize(frames, 0_f32);
+                    right.resize(frames, 0_f32);
 
-        let spec = hound::WavSpec {
-            channels: 2,
-            sample_rate: sample_rate
-                .try_into()
-                .expect("converting sample_rate into u32 failed"),
-            bits_per_sample: 32,
-            sample_format: hound::SampleFormat::Float,
+                    callback_sequencer
+                        .lock()
+                        .expect("locking sequencer failed")
+                        .render(&mut left[..frames], &mut right[..frames]);
+
+                    for (frame, sample) in data.chunks_mut(channels).zip(left.iter().zip(&right)) {
+                        frame[0] = *sample.0;
+                        if channels > 1 {
+                            frame[1] = *sample.1;
+                        }
+                    }
+                },
+                |err| eprintln!("audio stream error: {err}"),
+                None,
+            )
+            .expect("building output stream failed");
+
+        stream.play().expect("starting output stream failed");
+
+        *self.preview_stream.borrow_mut() = Some(stream);
+    }
+
+    fn input_select(&self) {
+        self.stop();
+
+        let Some(port_name) = self.input_combo.selection_string() else {
+            return;
         };
-        let mut writer = hound::WavWriter::create(self.output_path.text(), spec)
-            .expect("creating WAV writer failed");
 
-        for sample in left.iter().zip(right.iter()) {
-            writer
-                .write_sample(*sample.0)
-                .expect("writing wav left channel failed"); // left
-            writer
-                .write_sample(*sample.1)
-                .expect("writing wav right channel failed"); // right
+        let mut sf = File::open(self.sf_path.text()).expect("opening SoundFont failed");
+        let sound_font = Arc::new(SoundFont::new(&mut sf).expect("creating SoundFont failed"));
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .expect("finding default output device failed");
+        let config = device
+            .default_output_config()
+            .expect("getting default output config failed");
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0 as i32;
+
+        let mut settings = SynthesizerSettings::new(sample_rate);
+        settings.enable_reverb_and_chorus = checkbox_state_as_bool(self.reverb.check_state());
+        let synthesizer =
+            Synthesizer::new(&sound_font, &settings).expect("creating synthesizer failed");
+        let synthesizer = Arc::new(Mutex::new(synthesizer));
+
+        let stream_synth = Arc::clone(&synthesizer);
+        let mut left: Vec<f32> = vec![0_f32; 1024];
+        let mut right: Vec<f32> = vec![0_f32; 1024];
+
+        let stream = device
+            .build_output_stream(
+                &config.into(),
+                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                    let frames = data.len() / channels;
+                    left.resize(frames, 0_f32);
+                    right.resize(frames, 0_f32);
+
+                    stream_synth
+                        .lock()
+                        .expect("locking synthesizer failed")
+                        .render(&mut left[..frames], &mut right[..frames]);
+
+                    for (frame, sample) in data.chunks_mut(channels).zip(left.iter().zip(&right)) {
+                        frame[0] = *sample.0;
+                        if channels > 1 {
+                            frame[1] = *sample.1;
+                        }
+                    }
+                },
+                |err| eprintln!("audio stream error: {err}"),
+                None,
+            )
+            .expect("building output stream failed");
+
+        stream.play().expect("starting output stream failed");
+        *self.preview_stream.borrow_mut() = Some(stream);
+
+        let midi_in = midir::MidiInput::new("FireSynth input").expect("creating MIDI input failed");
+        let port = midi_in
+            .ports()
+            .into_iter()
+            .find(|port| {
+                midi_in
+                    .port_name(port)
+                    .expect("getting MIDI port name failed")
+                    == port_name
+            })
+            .expect("finding selected MIDI port failed");
+
+        let callback_synth = Arc::clone(&synthesizer);
+        let mt32_to_gm = checkbox_state_as_bool(self.mt32_to_gm.check_state());
+        let mixer = Arc::clone(&self.mixer);
+        let mut running_status = 0_u8;
+
+        let connection = midi_in
+            .connect(
+                &port,
+                "FireSynth input",
+                move |_timestamp, message, _| {
+                    let mut bytes = message;
+
+                    // System real-time bytes (0xF8 timing clock, 0xFE active sensing, ...) can
+                    // arrive continuously between channel-voice messages; they must not clobber
+                    // running status, or subsequent running-status-encoded notes/CCs would be
+                    // silently dropped until the next full status byte.
+                    if bytes.first().is_some_and(|byte| *byte >= 0xF8) {
+                        return;
+                    }
+
+                    if bytes.first().is_some_and(|byte| byte & 0x80 != 0) {
+                        running_status = bytes[0];
+                        bytes = &bytes[1..];
+                    }
+
+                    if running_status == 0 {
+                        return;
+                    }
+
+                    let channel = i32::from(running_status & 0x0F);
+                    let mut data1 = i32::from(*bytes.first().unwrap_or(&0));
+                    let mut data2 = i32::from(*bytes.get(1).unwrap_or(&0));
+
+                    if mt32_to_gm {
+                        match running_status & 0xF0 {
+                            0xC0 => {
+                                data1 = i32::from(MT32_TO_GM_PROGRAM[(data1 & 0x7F) as usize]);
+                            }
+                            0x80 | 0x90 if channel == 9 => {
+                                data1 = i32::from(mt32_key_to_gm_drum(data1 as u8));
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    if running_status & 0xF0 == 0x90 && data2 > 0 {
+                        let mixer_channels = mixer.lock().expect("locking mixer failed");
+                        let solo_active = mixer_channels.iter().any(|channel| channel.solo);
+                        if let Some(mixer_channel) =
+                            mixer_channels.get(usize::try_from(channel).unwrap_or(0))
+                        {
+                            if mixer_channel.mute || (solo_active && !mixer_channel.solo) {
+                                data2 = 0;
+                            } else {
+                                data2 = (f32::from(data2 as u8) * mixer_channel.gain)
+                                    .clamp(0.0, 127.0) as i32;
+                            }
+                        }
+                    }
+
+                    let mut synthesizer =
+                        callback_synth.lock().expect("locking synthesizer failed");
+
+                    match running_status & 0xF0 {
+                        0x80 => synthesizer.note_off(channel, data1),
+                        0x90 if data2 == 0 => synthesizer.note_off(channel, data1),
+                        0x90 => synthesizer.note_on(channel, data1, data2),
+                        0xA0..=0xE0 => {
+                            synthesizer.process_midi_message(
+                                channel,
+                                i32::from(running_status & 0xF0),
+                                data1,
+                                data2,
+                            );
+                        }
+                        _ => {}
+                    }
+                },
+                (),
+            )
+            .expect("connecting to MIDI input failed");
+
+        *self.midi_connection.borrow_mut() = Some(connection);
+    }
+
+    fn stop(&self) {
+        self.preview_stream.borrow_mut().take();
+        self.midi_connection.borrow_mut().take();
+    }
+
+    fn open_mixer(&self) {
+        if let Some(panel) = self.mixer_panel.borrow().as_ref() {
+            panel.window.set_visible(true);
+            return;
         }
 
-        nwg::modal_info_message(&self.window, "Finished", "Done");
+        let panel = open_mixer_panel(&self.mixer);
+        *self.mixer_panel.borrow_mut() = Some(panel);
     }
 
-    fn close(_: &Self) {
+    fn close(&self) {
+        self.stop();
         nwg::stop_thread_dispatch();
     }
 }